@@ -0,0 +1,209 @@
+use std::sync::Arc;
+
+use chrono::{Datelike, Utc};
+use regex::Regex;
+
+pub struct ParsedContract {
+    pub root: String,
+    pub month: usize,
+    pub year: usize,
+}
+
+/// Deconstructs a futures ticker into its base symbol and contract expiry. Different exchanges
+/// encode the month letter and expiry year differently, so each `--contract-scheme` gets its own
+/// implementation rather than a single global regex.
+pub trait ContractScheme: Send + Sync {
+    fn parse(&self, symbol: &str) -> Option<ParsedContract>;
+}
+
+// The standard futures month-letter convention (F=Jan, G=Feb, ... Z=Dec) is shared by CME, ICE and
+// most other exchanges; only the root/year encoding in the ticker itself tends to differ.
+fn month_letter_to_number(letter: &str) -> Result<usize, String> {
+    match letter {
+        "f" => Ok(1),
+        "g" => Ok(2),
+        "h" => Ok(3),
+        "j" => Ok(4),
+        "k" => Ok(5),
+        "m" => Ok(6),
+        "n" => Ok(7),
+        "q" => Ok(8),
+        "u" => Ok(9),
+        "v" => Ok(10),
+        "x" => Ok(11),
+        "z" => Ok(12),
+        _ => Err(format!("Invalid contract month: '{}'", letter))
+    }
+}
+
+fn complete_short_year(year: usize) -> usize {
+    if year >= 40 {
+        year + 1900
+    } else {
+        year + 2000
+    }
+}
+
+/// The historical, and still default, behaviour: CME-style tickers such as `@VXJ20` (root `@VX`,
+/// month `J` = April, year `20` = 2020).
+pub struct CmeContractScheme {
+    regex: Regex,
+}
+
+impl CmeContractScheme {
+    pub fn new() -> Self {
+        CmeContractScheme {
+            regex: Regex::new(r"^(?i)(?P<root>[@A-Z]+)(?P<month>[FGHJKMNQUVXZ])(?P<year>\d+)$").unwrap(),
+        }
+    }
+}
+
+impl ContractScheme for CmeContractScheme {
+    fn parse(&self, symbol: &str) -> Option<ParsedContract> {
+        let captures = self.regex.captures(symbol)?;
+
+        let root = captures.name("root").unwrap().as_str().to_string();
+        let month_letter = captures.name("month").unwrap().as_str().to_lowercase();
+        let year_short = captures.name("year").unwrap().as_str().parse::<usize>().unwrap();
+
+        Some(ParsedContract {
+            root,
+            month: month_letter_to_number(&month_letter).unwrap(),
+            year: complete_short_year(year_short),
+        })
+    }
+}
+
+/// ICE tickers follow the same root+month-letter convention as CME, but commonly abbreviate the
+/// expiry year to a single digit (e.g. `CLZ9` for December 2029 rather than `CLZ29`). A single
+/// digit is ambiguous across decades, so it's resolved against the current decade, rolling
+/// forward ten years if that would otherwise land more than a year in the past (a contract that
+/// expired last year is kept as last year's rather than moved nine years ahead).
+pub struct IceContractScheme {
+    regex: Regex,
+}
+
+impl IceContractScheme {
+    pub fn new() -> Self {
+        IceContractScheme {
+            regex: Regex::new(r"^(?i)(?P<root>[A-Z]+)(?P<month>[FGHJKMNQUVXZ])(?P<year>\d)$").unwrap(),
+        }
+    }
+
+    fn complete_single_digit_year(digit: usize) -> usize {
+        let current_year = Utc::now().year() as usize;
+        let decade_base = (current_year / 10) * 10;
+        let mut year = decade_base + digit;
+
+        if year + 1 < current_year {
+            year += 10;
+        }
+
+        year
+    }
+}
+
+impl ContractScheme for IceContractScheme {
+    fn parse(&self, symbol: &str) -> Option<ParsedContract> {
+        let captures = self.regex.captures(symbol)?;
+
+        let root = captures.name("root").unwrap().as_str().to_string();
+        let month_letter = captures.name("month").unwrap().as_str().to_lowercase();
+        let year_digit = captures.name("year").unwrap().as_str().parse::<usize>().unwrap();
+
+        Some(ParsedContract {
+            root,
+            month: month_letter_to_number(&month_letter).unwrap(),
+            year: Self::complete_single_digit_year(year_digit),
+        })
+    }
+}
+
+/// Builds its `ContractScheme`s once, up front (each wraps a compiled `Regex`), and hands out
+/// cheap `Arc` clones of them per file, shared across worker threads instead of recompiling a
+/// regex per file.
+pub struct SchemeRegistry {
+    explicit: Option<Arc<dyn ContractScheme>>,
+    cme: Arc<dyn ContractScheme>,
+    ice: Arc<dyn ContractScheme>,
+}
+
+impl SchemeRegistry {
+    /// `explicit` is `--contract-scheme`, if given; it's resolved once up front and pins every
+    /// file to that scheme regardless of its EXCHANGE segment.
+    pub fn new(explicit: Option<&str>) -> Self {
+        let explicit = explicit.map(|name| build(name).unwrap_or_else(|| panic!("Unknown contract scheme: '{}'", name)));
+
+        SchemeRegistry {
+            explicit,
+            cme: Arc::new(CmeContractScheme::new()),
+            ice: Arc::new(IceContractScheme::new()),
+        }
+    }
+
+    /// Resolves the scheme to use for a file: `explicit` if `--contract-scheme` was given,
+    /// otherwise one selected from the QuoteManager filename's EXCHANGE segment (see
+    /// `FileMetadata` in main.rs); the DATASOURCE segment is not consulted. CME remains the
+    /// fallback for unrecognized exchanges so existing QuoteManager exports keep parsing as before.
+    pub fn resolve(&self, _datasource: &str, exchange: &str) -> Arc<dyn ContractScheme> {
+        if let Some(scheme) = &self.explicit {
+            return scheme.clone();
+        }
+
+        match exchange {
+            "ice" => self.ice.clone(),
+            _ => self.cme.clone(),
+        }
+    }
+}
+
+fn build(name: &str) -> Option<Arc<dyn ContractScheme>> {
+    match name {
+        "cme" => Some(Arc::new(CmeContractScheme::new())),
+        "ice" => Some(Arc::new(IceContractScheme::new())),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn month_letter_to_number_covers_all_futures_months() {
+        assert_eq!(month_letter_to_number("f"), Ok(1));
+        assert_eq!(month_letter_to_number("z"), Ok(12));
+        assert!(month_letter_to_number("a").is_err());
+    }
+
+    #[test]
+    fn complete_short_year_splits_on_the_pivot() {
+        assert_eq!(complete_short_year(20), 2020);
+        assert_eq!(complete_short_year(39), 2039);
+        assert_eq!(complete_short_year(40), 1940);
+        assert_eq!(complete_short_year(99), 1999);
+    }
+
+    #[test]
+    fn cme_scheme_accepts_year_lengths_other_than_two_digits() {
+        // Baseline parity: the CME year group must stay `\d+`, not `\d{2}`, or three/four-digit
+        // years in existing QuoteManager exports would stop parsing.
+        let scheme = CmeContractScheme::new();
+        assert!(scheme.parse("@VXJ2020").is_some());
+        assert!(scheme.parse("@VXJ20").is_some());
+    }
+
+    #[test]
+    fn ice_single_digit_year_resolves_within_the_current_decade() {
+        // `complete_single_digit_year` reads `Utc::now()`, so assert on structure rather than a
+        // pinned year: the result must share the current decade unless it rolled forward by ten.
+        let current_year = Utc::now().year() as usize;
+        let decade_base = (current_year / 10) * 10;
+
+        for digit in 0..10 {
+            let year = IceContractScheme::complete_single_digit_year(digit);
+            assert!(year == decade_base + digit || year == decade_base + digit + 10);
+            assert!(year + 1 >= current_year);
+        }
+    }
+}
@@ -1,11 +1,22 @@
 use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::net::IpAddr;
+use std::time::Duration;
 
 extern crate spmc;
 
+mod contract_scheme;
+
+use backoff::{retry, Error as BackoffError, ExponentialBackoff};
+use chrono::{NaiveDate, NaiveDateTime};
 use clap::{Arg, App};
+use native_tls::{Certificate, TlsConnector};
 use postgres::{Config, NoTls};
-use postgres::types::Type;
-use regex::Regex;
+use postgres::error::SqlState;
+use postgres_native_tls::MakeTlsConnector;
 use rpassword::prompt_password_stdout;
 use walkdir::{WalkDir, DirEntry};
 
@@ -28,32 +39,22 @@ fn csv_filter(entry: &DirEntry) -> bool {
     }
 }
 
-fn cme_month_letter_to_number(letter: &str) -> Result<usize, String> {
-    match letter {
-        "f" => Ok(1),
-        "g" => Ok(2),
-        "j" => Ok(4),
-        "k" => Ok(5),
-        "h" => Ok(3),
-        "m" => Ok(6),
-        "n" => Ok(7),
-        "q" => Ok(8),
-        "u" => Ok(9),
-        "v" => Ok(10),
-        "x" => Ok(11),
-        "z" => Ok(12),
-        _ => Err(format!("Invalid contract month: '{}'", letter))
-    }
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TlsMode {
+    Prefer,
+    Require,
+    VerifyFull,
 }
 
-fn complete_short_year(year: &usize) -> usize {
-    if *year >= 40 {
-        *year + 1900
-    } else if *year < 40 {
-        *year + 2000
-    } else {
-        *year
-    } 
+impl TlsMode {
+    fn from_str(mode: &str) -> Result<TlsMode, String> {
+        match mode {
+            "prefer" => Ok(TlsMode::Prefer),
+            "require" => Ok(TlsMode::Require),
+            "verify-full" => Ok(TlsMode::VerifyFull),
+            _ => Err(format!("Invalid TLS mode: '{}'", mode))
+        }
+    }
 }
 
 fn command_usage<'a, 'b>() -> App<'a, 'b> {
@@ -61,10 +62,12 @@ fn command_usage<'a, 'b>() -> App<'a, 'b> {
     const DEFAULT_PORT: &str = "5432";
     const DEFAULT_USER: &str = "postgres";
     const DEFAULT_THREADS: &str = "10";
+    const DEFAULT_TLS_MODE: &str = "prefer";
+    const DEFAULT_CONNECT_TIMEOUT: &str = "60";
 
     App::new("csv_to_postgresql")
     .author("Matthew Scheffel <matt@dataheck.com>")
-    .about("Inserts market data into a PostgreSQL database from CSV-formatted files. TLS not supported.")
+    .about("Inserts market data into a PostgreSQL database from CSV-formatted files.")
     .long_about("Designed for the insertion of market data exported from MultiCharts QuoteManager.
     Expects a QuoteManager-standard naming convention: SYMBOL-DATASOURCE-EXCHANGE-TYPE-TIMEFRAME-FIELD.csv.
     SYMBOLs are deconstructed if they appear to be Futures, and will be converted to the base symbol with a new CONTRACT field added.
@@ -125,12 +128,113 @@ fn command_usage<'a, 'b>() -> App<'a, 'b> {
             .default_value(DEFAULT_THREADS)
             .help("The number of threads (and PostgreSQL connections) to use for insertion.")
     )
+    .arg(
+        Arg::with_name("tls")
+            .long("tls")
+            .takes_value(false)
+            .help("Encrypt the connection to the PostgreSQL server with TLS.")
+    )
+    .arg(
+        Arg::with_name("tls-mode")
+            .long("tls-mode")
+            .takes_value(true)
+            .default_value(DEFAULT_TLS_MODE)
+            .possible_values(&["prefer", "require", "verify-full"])
+            .help("How strictly to validate the server's TLS certificate. Only used with --tls.")
+    )
+    .arg(
+        Arg::with_name("tls-root-cert")
+            .long("tls-root-cert")
+            .takes_value(true)
+            .help("Path to a PEM-encoded root certificate to trust, in addition to the system roots. Only used with --tls.")
+    )
+    .arg(
+        Arg::with_name("hostaddr")
+            .long("hostaddr")
+            .takes_value(true)
+            .help("Numeric IP address of the PostgreSQL server. When given, skips DNS resolution of --host for the TCP connection; --host is still sent for TLS SNI / server host matching.")
+    )
+    .arg(
+        Arg::with_name("connect-timeout")
+            .long("connect-timeout")
+            .takes_value(true)
+            .default_value(DEFAULT_CONNECT_TIMEOUT)
+            .help("Maximum number of seconds to retry a connection attempt (with exponential backoff) before giving up.")
+    )
+    .arg(
+        Arg::with_name("contract-scheme")
+            .long("contract-scheme")
+            .takes_value(true)
+            .possible_values(&["cme", "ice"])
+            .help("Futures symbol/contract parsing convention to use. Auto-detected from each file's EXCHANGE segment if not given (e.g. 'ice' selects ICE-style single-digit expiry years); defaults to CME-style tickers.")
+    )
+}
+
+// Only a dropped/refused socket is worth retrying; auth failures and other permanent errors
+// should fail fast instead of burning the whole --connect-timeout budget.
+fn is_transient_connect_error(err: &postgres::Error) -> bool {
+    err.source()
+        .and_then(|source| source.downcast_ref::<io::Error>())
+        .map(|io_err| matches!(
+            io_err.kind(),
+            io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted
+        ))
+        .unwrap_or(false)
+}
+
+fn connect_with_retry<F>(connect_timeout: Duration, connect: F) -> postgres::Client
+where
+    F: FnMut() -> Result<postgres::Client, postgres::Error>,
+{
+    let mut connect = connect;
+
+    let backoff = ExponentialBackoff {
+        initial_interval: Duration::from_millis(200),
+        multiplier: 2.0,
+        max_interval: Duration::from_secs(30),
+        max_elapsed_time: Some(connect_timeout),
+        ..ExponentialBackoff::default()
+    };
+
+    let result = retry(backoff, || {
+        connect().map_err(|err| {
+            if is_transient_connect_error(&err) {
+                BackoffError::transient(err)
+            } else {
+                BackoffError::permanent(err)
+            }
+        })
+    });
+
+    match result {
+        Ok(client) => client,
+        Err(BackoffError::Permanent(err)) => fatal_connection_error(&err),
+        Err(BackoffError::Transient { err, .. }) => fatal_connection_error(&err),
+    }
+}
+
+// Prints a clean message for a fatal connection-level error and exits the process, rather than
+// letting the caller's .unwrap() panic. Used anywhere a lost/rejected connection can't be worked
+// around locally (invalid credentials, the server refusing/dropping the socket).
+fn fatal_connection_error(err: &postgres::Error) -> ! {
+    match err.code() {
+        Some(code) if *code == SqlState::INVALID_PASSWORD => {
+            eprintln!("Fatal: invalid password for the PostgreSQL connection.");
+        },
+        Some(code) => {
+            eprintln!("Fatal: could not connect to PostgreSQL (SQLSTATE {}): {}", code.code(), err);
+        },
+        None => {
+            eprintln!("Fatal: could not connect to PostgreSQL: {}", err);
+        }
+    }
+    std::process::exit(1);
 }
 
 fn create_tables(client: &mut postgres::Client) -> Result<usize, postgres::Error> {
-    client.batch_execute(r#"
+    let result = client.batch_execute(r#"
         CREATE TABLE bars (
-            "timestamp" timestamp with time zone not null, 
+            "timestamp" timestamp with time zone not null,
             contract date,
             symbol text collate pg_catalog."default" not null,
             open numeric,
@@ -144,20 +248,95 @@ fn create_tables(client: &mut postgres::Client) -> Result<usize, postgres::Error
         );
         CREATE INDEX symbol_idx ON bars (symbol);
         CREATE INDEX symbol_contract_idx ON bars (symbol, contract);
-    "#)?;
-    Ok(0)
+    "#);
+
+    match result {
+        Ok(()) => Ok(0),
+        Err(err) if err.code() == Some(&SqlState::DUPLICATE_TABLE) => {
+            println!("Warning: 'bars' already exists, skipping table creation.");
+            Ok(0)
+        },
+        Err(err) => Err(err),
+    }
 }
 
-fn prepare_client(host: Arc<String>, port: Arc<u16>, user: Arc<String>, dbname: Arc<String>, password: Arc<String>) -> postgres::Client {
-    let client = Config::new()
-        .host(&host)
-        .port(*port)
-        .user(&user)
-        .dbname(&dbname)
-        .password(password.to_string())
-        .connect(NoTls).unwrap();
+fn build_tls_connector(tls_mode: TlsMode, root_cert_path: Option<&str>) -> MakeTlsConnector {
+    let mut builder = TlsConnector::builder();
+
+    // "verify-full" checks both the certificate chain and that the hostname matches, same as
+    // libpq's sslmode semantics. "prefer" only guarantees the transport is encrypted and never
+    // validates anything. "require" also skips hostname matching, but if the caller supplied
+    // --tls-root-cert it still validates the chain against it (libpq's verify-ca behaviour under
+    // sslmode=require), rather than silently ignoring the root cert they gave us.
+    match tls_mode {
+        TlsMode::VerifyFull => {},
+        TlsMode::Require => {
+            builder.danger_accept_invalid_hostnames(true);
+            if root_cert_path.is_none() {
+                builder.danger_accept_invalid_certs(true);
+            }
+        },
+        TlsMode::Prefer => {
+            builder.danger_accept_invalid_certs(true);
+            builder.danger_accept_invalid_hostnames(true);
+        },
+    }
 
-    client
+    if let Some(path) = root_cert_path {
+        let cert_bytes = fs::read(path).expect(&format!("Could not read TLS root certificate: '{}'", path));
+        let cert = Certificate::from_pem(&cert_bytes).expect("TLS root certificate must be PEM-encoded");
+        builder.add_root_certificate(cert);
+    }
+
+    let connector = builder.build().expect("Failed to build TLS connector");
+    MakeTlsConnector::new(connector)
+}
+
+// Bundles the connection parameters that every worker thread carries around, so adding another
+// one (as --hostaddr did) doesn't keep growing prepare_client_notls/prepare_client_tls's argument
+// lists.
+#[derive(Clone)]
+struct ConnectionParams {
+    host: Arc<String>,
+    port: Arc<u16>,
+    user: Arc<String>,
+    dbname: Arc<String>,
+    password: Arc<String>,
+    hostaddr: Option<IpAddr>,
+}
+
+// `Config::hostaddr` takes the `IpAddr` overload added in postgres >= 0.19.7 (tokio-postgres
+// >= 0.7.9); this crate must be pinned to at least that version for the `--hostaddr` flag below
+// to compile.
+fn prepare_client_notls(params: ConnectionParams, connect_timeout: Duration) -> postgres::Client {
+    connect_with_retry(connect_timeout, || {
+        let mut config = Config::new();
+        config.host(&params.host).port(*params.port).user(&params.user).dbname(&params.dbname).password(params.password.to_string());
+
+        if let Some(addr) = params.hostaddr {
+            config.hostaddr(addr);
+        }
+
+        config.connect(NoTls)
+    })
+}
+
+// See the `hostaddr` version note on `prepare_client_notls` above; the TLS path takes the same
+// `IpAddr` overload.
+fn prepare_client_tls(params: ConnectionParams, tls: MakeTlsConnector, connect_timeout: Duration) -> postgres::Client {
+    connect_with_retry(connect_timeout, || {
+        let mut config = Config::new();
+        config.host(&params.host).port(*params.port).user(&params.user).dbname(&params.dbname).password(params.password.to_string());
+
+        if let Some(addr) = params.hostaddr {
+            config.hostaddr(addr);
+        }
+
+        // Refuse to fall back to plaintext if the server doesn't offer TLS.
+        config.ssl_mode(postgres::config::SslMode::Require);
+
+        config.connect(tls.clone())
+    })
 }
 
 type Record = HashMap<String, String>;
@@ -171,14 +350,43 @@ struct FileMetadata<'a> {
     field: &'a str,
 }
 
-fn process_file(entry_value: DirEntry, futures_regex: Arc<regex::Regex>, client: &mut postgres::Client) {
+// COPY's text format treats backslash, tab and newline specially; escape backslash first so we
+// don't double-escape the backslashes introduced by the later replacements.
+fn copy_escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn copy_row(fields: &[&str]) -> String {
+    let mut line: String = fields.iter()
+        .map(|field| if field.is_empty() { String::from("\\N") } else { copy_escape(field) })
+        .collect::<Vec<String>>()
+        .join("\t");
+    line.push('\n');
+    line
+}
+
+fn parse_day_timestamp(date: &str) -> Option<String> {
+    let parsed = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+    Some(parsed.and_hms_opt(0, 0, 0).unwrap().format("%Y-%m-%dT%H:%M:%S").to_string())
+}
+
+fn parse_minute_timestamp(date: &str, time: &str) -> Option<String> {
+    let parsed = NaiveDateTime::parse_from_str(&format!("{} {}", date, time), "%Y-%m-%d %H:%M:%S").ok()?;
+    Some(parsed.format("%Y-%m-%dT%H:%M:%S").to_string())
+}
+
+fn process_file(entry_value: DirEntry, scheme_registry: &contract_scheme::SchemeRegistry, client: &mut postgres::Client) -> Result<(), postgres::Error> {
     let lowercase_file_name = entry_value.path().file_stem().unwrap().to_str().unwrap().to_lowercase();
     let name_segments: Vec<&str> = lowercase_file_name.split('-').collect();
     // 0=symbol, 1=datasource, 2=exchange, 3=type, 4=time, 5=field
-    
+
     if name_segments.len() != 6 {
         println!("Filename does not meet expected pattern ('symbol-datasource-exchange-type-time-field.csv'), skipping. File: {}", lowercase_file_name);
-        return;
+        return Ok(());
     }
 
     let metadata = FileMetadata{
@@ -190,89 +398,138 @@ fn process_file(entry_value: DirEntry, futures_regex: Arc<regex::Regex>, client:
         field: name_segments[5]
     };
 
-    // deconstruct CME futures short contract names, ex: @VXJ20 -> @VX, April, 2020.
-    let (symbol_root, contract_month, contract_year) = match futures_regex.captures(name_segments[0]) {
-        Some(x) => (
-            x.name("root").unwrap().as_str(), // i.e., root of @VXJ20 is @VX
-            x.name("month").unwrap().as_str(), 
-            x.name("year").unwrap().as_str().parse::<usize>().unwrap()
-        ),
-        None => (metadata.symbol, "", 0)
-    };
-
-    let contract_date:String = match (contract_month, contract_year) {
-        ("", 0) => String::from(""),
-        (month, year) => {
-            let year_number = complete_short_year(&year);
-            let month_number = cme_month_letter_to_number(&month).unwrap();
+    // deconstruct futures short contract names, ex: @VXJ20 -> @VX, April, 2020.
+    let scheme = scheme_registry.resolve(metadata.datasource, metadata.exchange);
 
-            format!("{year}-{month:02}-{day:02}", year=year_number, month=month_number, day=1)
+    let (symbol_root, contract_date) = match scheme.parse(name_segments[0]) {
+        Some(parsed) => {
+            let contract_date = format!("{year}-{month:02}-{day:02}", year=parsed.year, month=parsed.month, day=1);
+            (parsed.root, contract_date)
         },
+        None => (metadata.symbol.to_string(), String::from("")),
     };
 
     if metadata.timeframe != "day" && metadata.timeframe != "minute" {
         println!("Timeframe not supported, skipped: {}", lowercase_file_name);
-        return;
+        return Ok(());
     }
 
     let mut reader = csv::Reader::from_path(entry_value.path());
 
     match reader.as_mut() {
         Ok(r) => {
-            let insert_day_statement = client.prepare_typed(
-                r#"
-                INSERT INTO bars ("timestamp", symbol, contract, open, high, low, close, volume, barsize) 
-                VALUES(
-                TO_TIMESTAMP($1, 'YYYY-MM-DD'), $2, TO_TIMESTAMP($3, 'YYYY-MM-DD'), CAST($4 AS numeric), CAST($5 AS numeric),
-                CAST($6 AS numeric), CAST($7 AS numeric), CAST($8 AS numeric), $9
-                )
-                ON CONFLICT ON CONSTRAINT bars_daily_pkey DO NOTHING"#,
-                &[Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT]
-            ).unwrap();    
-        
-            let insert_minute_statement = client.prepare_typed(
-                r#"
-                INSERT INTO bars ("timestamp", symbol, contract, open, high, low, close, volume, barsize) 
-                VALUES(
-                TO_TIMESTAMP(CONCAT($1, ' ', $2), 'YYYY-MM-DD HH24:MI:SS'), $3, TO_TIMESTAMP($4, 'YYYY-MM-DD'), CAST($5 AS numeric), CAST($6 AS numeric),
-                CAST($7 AS numeric), CAST($8 AS numeric), CAST($9 AS numeric), $10
-                )
-                ON CONFLICT ON CONSTRAINT bars_daily_pkey DO NOTHING"#,
-                &[Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT]
-            ).unwrap();
+            // The staging table is TEMP (session/connection-scoped) and this client is reused
+            // across files, so a prior file that errored out between CREATE and DROP (e.g. a
+            // malformed row failing the COPY) would otherwise leave the table behind and poison
+            // every later file on this connection with a DUPLICATE_TABLE error. Drop it first so
+            // each file starts from a clean slate regardless of how the previous one ended.
+            client.batch_execute("DROP TABLE IF EXISTS bars_staging")?;
+
+            client.batch_execute(
+                r#"CREATE TEMP TABLE bars_staging (
+                    "timestamp" timestamp with time zone,
+                    symbol text,
+                    contract date,
+                    open numeric,
+                    high numeric,
+                    low numeric,
+                    close numeric,
+                    volume numeric,
+                    barsize text
+                )"#
+            )?;
+
+            let mut writer = client.copy_in(
+                r#"COPY bars_staging ("timestamp", symbol, contract, open, high, low, close, volume, barsize) FROM STDIN (FORMAT text)"#
+            )?;
 
             for row_result in r.deserialize() {
-                let row: Record = row_result.unwrap();
-
-                match metadata.timeframe {
-                    "day" => {
-                        client.execute(
-                            &insert_day_statement, 
-                            &[
-                                &row["Date"], &symbol_root, &contract_date, 
-                                &row["Open"], &row["High"], &row["Low"], &row["Close"], &row["TotalVolume"],
-                                &metadata.timeframe
-                            ]
-                        ).unwrap();
-                    },
+                let row: Record = match row_result {
+                    Ok(row) => row,
+                    Err(err) => {
+                        println!("Skipping malformed row in {}: {}", lowercase_file_name, err);
+                        continue;
+                    }
+                };
+
+                let required_fields = ["Date", "Open", "High", "Low", "Close", "TotalVolume"];
+                if let Some(missing) = required_fields.iter().find(|field| !row.contains_key(**field)) {
+                    println!("Skipping row missing '{}' column in {}", missing, lowercase_file_name);
+                    continue;
+                }
+
+                // COPY's numeric columns reject anything that isn't a valid number at the whole-
+                // statement level (SQLSTATE 22P02), and the later INSERT...SELECT commits or skips
+                // the staged rows as a single unit. Validating here, like the missing-column checks
+                // above, keeps a single bad value from taking the rest of the file's rows with it.
+                let numeric_fields = ["Open", "High", "Low", "Close", "TotalVolume"];
+                if let Some(bad_field) = numeric_fields.iter().find(|field| row[**field].parse::<f64>().is_err()) {
+                    println!("Skipping row with non-numeric '{}' value in {}", bad_field, lowercase_file_name);
+                    continue;
+                }
+
+                let parsed_timestamp = match metadata.timeframe {
+                    "day" => parse_day_timestamp(&row["Date"]),
                     "minute" => {
-                        client.execute(
-                            &insert_minute_statement,
-                            &[
-                                &row["Date"], &row["Time"], &symbol_root, &contract_date, 
-                                &row["Open"], &row["High"], &row["Low"], &row["Close"], &row["TotalVolume"],
-                                &metadata.timeframe
-                            ]
-                        ).unwrap();
+                        if !row.contains_key("Time") {
+                            println!("Skipping row missing 'Time' column in {}", lowercase_file_name);
+                            continue;
+                        }
+                        parse_minute_timestamp(&row["Date"], &row["Time"])
                     },
-                    _ => {
-                        break; // should be impossible, we checked earlier
+                    _ => break, // should be impossible, we checked earlier
+                };
+
+                let timestamp = match parsed_timestamp {
+                    Some(timestamp) => timestamp,
+                    None => {
+                        println!("Skipping row with unparseable Date/Time in {}", lowercase_file_name);
+                        continue;
                     }
+                };
+
+                let line = copy_row(&[
+                    &timestamp, &symbol_root, &contract_date,
+                    &row["Open"], &row["High"], &row["Low"], &row["Close"], &row["TotalVolume"],
+                    metadata.timeframe
+                ]);
+
+                if let Err(err) = writer.write_all(line.as_bytes()) {
+                    println!("Skipping row that failed to stream in {}: {}", lowercase_file_name, err);
+                    continue;
                 }
-            }                
+            }
+
+            writer.finish()?;
+
+            let insert_result = client.batch_execute(
+                r#"INSERT INTO bars ("timestamp", symbol, contract, open, high, low, close, volume, barsize)
+                SELECT "timestamp", symbol, contract, open, high, low, close, volume, barsize FROM bars_staging
+                ON CONFLICT ON CONSTRAINT bars_daily_pkey DO NOTHING"#
+            );
+
+            client.batch_execute("DROP TABLE bars_staging")?;
+
+            match insert_result {
+                Ok(()) => Ok(()),
+                // Unlike the old per-row INSERT, this INSERT...SELECT is one statement for the
+                // whole file: a UNIQUE/NOT NULL violation here skips the *entire file*, not just
+                // the offending row. Duplicate primary keys, within the file or against rows
+                // already in bars, are absorbed by ON CONFLICT ... DO NOTHING, so what reaches
+                // here is a NOT NULL violation (e.g. the NULL contract of a non-futures file, as
+                // contract is part of the primary key) or a conflict on another unique constraint.
+                // That's an intentional trade-off of batching the insert for throughput, so treat
+                // it as a file-level skip-and-log.
+                Err(err) if err.code() == Some(&SqlState::UNIQUE_VIOLATION) || err.code() == Some(&SqlState::NOT_NULL_VIOLATION) => {
+                    println!("Skipping file due to constraint violation: {} ({})", lowercase_file_name, err);
+                    Ok(())
+                },
+                Err(err) => Err(err),
+            }
         },
         Err(_) => {
-            println!("Error with file, skipped: {}", lowercase_file_name)
+            println!("Error with file, skipped: {}", lowercase_file_name);
+            Ok(())
         }
     }
 }
@@ -285,74 +542,90 @@ fn main() {
     let postgresql_dbname = Arc::new(matches.value_of("database").unwrap().to_string());
     let postgresql_port = Arc::new(matches.value_of("port").unwrap().parse::<u16>().expect(&format!("Invalid port specified: '{}.'", matches.value_of("port").unwrap())));
     let max_threads = matches.value_of("threads").unwrap().parse::<usize>().expect(&format!("Invalid thread count specified: '{}.'", matches.value_of("threads").unwrap()));
+    let connect_timeout = Duration::from_secs(matches.value_of("connect-timeout").unwrap().parse::<u64>().expect(&format!("Invalid connect timeout specified: '{}.'", matches.value_of("connect-timeout").unwrap())));
+    let postgresql_hostaddr = matches.value_of("hostaddr").map(|addr| addr.parse::<IpAddr>().expect(&format!("Invalid hostaddr specified: '{}.'", addr)));
 
     println!("Connecting to PostgreSQL {}:{} as user '{}'.", postgresql_host, postgresql_port, postgresql_user);
 
     let postgresql_pass = Arc::new(prompt_password_stdout("Password: ").unwrap());
 
+    let connection_params = ConnectionParams {
+        host: postgresql_host.clone(),
+        port: postgresql_port.clone(),
+        user: postgresql_user.clone(),
+        dbname: postgresql_dbname.clone(),
+        password: postgresql_pass.clone(),
+        hostaddr: postgresql_hostaddr,
+    };
+
+    let tls_connector: Option<Arc<MakeTlsConnector>> = if matches.is_present("tls") {
+        let tls_mode = TlsMode::from_str(matches.value_of("tls-mode").unwrap()).unwrap();
+        Some(Arc::new(build_tls_connector(tls_mode, matches.value_of("tls-root-cert"))))
+    } else {
+        None
+    };
+
     if matches.is_present("create") {
         println!("Creating tables.");
-        
-        let postgresql_host = postgresql_host.clone();
-        let postgresql_port = postgresql_port.clone();
-        let postgresql_user = postgresql_user.clone();
-        let postgresql_dbname = postgresql_dbname.clone();
-        let postgresql_pass = postgresql_pass.clone();
-
-        let mut client = prepare_client(
-            postgresql_host, 
-            postgresql_port, 
-            postgresql_user, 
-            postgresql_dbname, 
-            postgresql_pass
-        );
-        create_tables(&mut client).unwrap();
+
+        let mut client = match &tls_connector {
+            Some(tls) => prepare_client_tls(connection_params.clone(), (**tls).clone(), connect_timeout),
+            None => prepare_client_notls(connection_params.clone(), connect_timeout),
+        };
+        if let Err(err) = create_tables(&mut client) {
+            eprintln!("Fatal: could not create 'bars' table: {}", err);
+            std::process::exit(1);
+        }
     }
 
-    let futures_regex = std::sync::Arc::new(Regex::new(r"^(?i)(?P<root>[@A-Z]+)(?P<month>[FGHJKMNQUVXZ])(?P<year>\d+)$").unwrap());
+    let scheme_registry = Arc::new(contract_scheme::SchemeRegistry::new(matches.value_of("contract-scheme")));
 
     let target_path = matches.value_of("directory").unwrap();
     println!("Transversing path '{}'", target_path);
 
-    let (mut tx, rx) = spmc::channel();
+    let (mut tx, rx): (spmc::Sender<DirEntry>, spmc::Receiver<DirEntry>) = spmc::channel();
     let mut thread_handles = Vec::new();
 
     for _n in 0..max_threads {
         let rx = rx.clone();
 
-        let futures_regex = futures_regex.clone();
-        let postgresql_host = postgresql_host.clone();
-        let postgresql_port = postgresql_port.clone();
-        let postgresql_user = postgresql_user.clone();
-        let postgresql_dbname = postgresql_dbname.clone();
-        let postgresql_pass = postgresql_pass.clone();        
+        let scheme_registry = scheme_registry.clone();
+        let connection_params = connection_params.clone();
+        let tls_connector = tls_connector.clone();
 
         thread_handles.push(thread::spawn(move || {
-            let postgresql_host = postgresql_host.clone();
-            let postgresql_port = postgresql_port.clone();
-            let postgresql_user = postgresql_user.clone();
-            let postgresql_dbname = postgresql_dbname.clone();
-            let postgresql_pass = postgresql_pass.clone();
-
-            let mut client = prepare_client(
-                postgresql_host, 
-                postgresql_port, 
-                postgresql_user, 
-                postgresql_dbname, 
-                postgresql_pass
-            );
+            let new_client = || match &tls_connector {
+                Some(tls) => prepare_client_tls(connection_params.clone(), (**tls).clone(), connect_timeout),
+                None => prepare_client_notls(connection_params.clone(), connect_timeout),
+            };
 
-            loop {
-                let futures_regex = futures_regex.clone();
+            let mut client = new_client();
 
+            loop {
                 let entry_result = rx.recv();
                 match entry_result {
                     Ok(entry_value) => {
-                        process_file(
-                            entry_value, 
-                            futures_regex, 
-                            &mut client
-                        );
+                        let file_name = entry_value.path().to_string_lossy().to_string();
+
+                        if let Err(err) = process_file(entry_value, &scheme_registry, &mut client) {
+                            match err.code() {
+                                Some(code) if *code == SqlState::INVALID_PASSWORD => {
+                                    fatal_connection_error(&err);
+                                },
+                                // A dropped/refused connection is recoverable: re-establish it (with
+                                // its own backoff/retry budget) before pulling the next file rather
+                                // than abandoning the rest of this thread's work. A dropped socket
+                                // surfaces as an error with no SQLSTATE; a server-reported
+                                // connection exception as SQLSTATE class 08.
+                                code if code.map_or(true, |code| code.code().starts_with("08")) => {
+                                    println!("Lost connection while processing {} ({}), reconnecting...", file_name, err);
+                                    client = new_client();
+                                },
+                                _ => {
+                                    println!("Skipping file due to database error, continuing with next file: {} ({})", file_name, err);
+                                }
+                            }
+                        }
                     },
                     Err(_) => {
                         println!("All work complete, thread shutdown.");
@@ -383,3 +656,19 @@ fn main() {
         handle.join().unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copy_escape_escapes_copy_text_format_specials() {
+        assert_eq!(copy_escape("a\\b\tc\nd\re"), "a\\\\b\\tc\\nd\\re");
+        assert_eq!(copy_escape("plain"), "plain");
+    }
+
+    #[test]
+    fn copy_row_joins_fields_with_tabs_and_nulls_empty_ones() {
+        assert_eq!(copy_row(&["a", "", "c"]), "a\t\\N\tc\n");
+    }
+}